@@ -4,42 +4,179 @@ use std::collections::HashMap;
 
 use flate2;
 use flate2::write::GzEncoder;
+use flate2::write::DeflateEncoder;
 use flate2::Compression;
 
+use brotli;
+use brotli::CompressorWriter;
+
 use std::thread;
 use std::fs;
 use std::env;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use httpdate::{fmt_http_date, parse_http_date};
+
+// How long a connection may sit idle between requests before it is reaped
+const CONNECTION_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Bounds enforced while parsing a request, so a hostile or broken client
+// can't make the server buffer an unbounded amount of data
+const MAX_REQUEST_LINE_LEN: usize = 8 * 1024;
+const MAX_HEADER_LINE_LEN: usize = 8 * 1024;
+const MAX_HEADER_COUNT: usize = 100;
+const MAX_HEADER_BYTES: usize = 16 * 1024;
+const MAX_CHUNKED_BODY_SIZE: usize = 10 * 1024 * 1024;
+const MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+
+// A parse failure paired with the HTTP status it should be reported as
+struct RequestError {
+    status_code: u16,
+    status_msg: &'static str,
+    message: String,
+}
+
+impl RequestError {
+    fn bad_request(message: impl Into<String>) -> Self {
+        RequestError { status_code: 400, status_msg: "Bad Request", message: message.into() }
+    }
+
+    fn too_large(message: impl Into<String>) -> Self {
+        RequestError { status_code: 431, status_msg: "Request Header Fields Too Large", message: message.into() }
+    }
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+// Reads a single CRLF/LF-terminated line off `reader`, capped at `max_len`
+// bytes. Returns `Ok(None)` on a clean EOF (or idle timeout) before any
+// bytes arrive, and a `431` error if the line doesn't fit in the cap.
+fn read_bounded_line(reader: &mut BufReader<&mut TcpStream>, max_len: usize) -> Result<Option<String>, RequestError> {
+    let mut buf = Vec::new();
+    let bytes_read = {
+        let mut limited = reader.by_ref().take((max_len + 1) as u64);
+        match limited.read_until(b'\n', &mut buf) {
+            Ok(n) => n,
+            Err(e) if buf.is_empty() && (e.kind() == std::io::ErrorKind::WouldBlock
+                || e.kind() == std::io::ErrorKind::TimedOut) => {
+                return Ok(None);
+            }
+            Err(e) => return Err(RequestError::bad_request(format!("Error reading line: {}", e))),
+        }
+    };
+
+    if bytes_read == 0 {
+        return Ok(None);
+    }
+
+    if buf.len() > max_len {
+        return Err(RequestError::too_large("Line exceeds the configured limit"));
+    }
+
+    while matches!(buf.last(), Some(b'\n') | Some(b'\r')) {
+        buf.pop();
+    }
+
+    String::from_utf8(buf).map(Some).map_err(|_| RequestError::bad_request("Line is not valid UTF-8"))
+}
+
+// Decodes a `Transfer-Encoding: chunked` body: each chunk is a hex size
+// line, that many bytes, then a trailing CRLF, until a zero-length chunk
+// followed by optional trailer headers and the final blank line.
+fn read_chunked_body(reader: &mut BufReader<&mut TcpStream>) -> Result<Vec<u8>, RequestError> {
+    let mut body = Vec::new();
+
+    loop {
+        let size_line = match read_bounded_line(reader, MAX_HEADER_LINE_LEN)? {
+            Some(line) => line,
+            None => return Err(RequestError::bad_request("Connection closed while reading a chunk size")),
+        };
+
+        let size_token = size_line.split(';').next().unwrap_or("").trim();
+        let chunk_size = usize::from_str_radix(size_token, 16)
+            .map_err(|_| RequestError::bad_request(format!("Invalid chunk size: {}", size_line)))?;
+
+        if chunk_size == 0 {
+            // Consume the (discarded) trailer headers up to the blank line
+            loop {
+                match read_bounded_line(reader, MAX_HEADER_LINE_LEN)? {
+                    Some(line) if line.is_empty() => break,
+                    Some(_) => continue,
+                    None => return Err(RequestError::bad_request("Connection closed while reading chunk trailers")),
+                }
+            }
+            break;
+        }
+
+        if chunk_size > MAX_CHUNKED_BODY_SIZE.saturating_sub(body.len()) {
+            return Err(RequestError::too_large("Chunked body exceeds the configured limit"));
+        }
+
+        let mut chunk = vec![0u8; chunk_size];
+        reader.read_exact(&mut chunk)
+            .map_err(|e| RequestError::bad_request(format!("Error reading chunk: {}", e)))?;
+        body.extend_from_slice(&chunk);
 
+        let mut crlf = [0u8; 2];
+        reader.read_exact(&mut crlf)
+            .map_err(|e| RequestError::bad_request(format!("Error reading chunk terminator: {}", e)))?;
+        if &crlf != b"\r\n" {
+            return Err(RequestError::bad_request("Malformed chunk terminator"));
+        }
+    }
+
+    Ok(body)
+}
+
+#[derive(PartialEq, Clone, Copy)]
 enum HTTPMethod {
-    GET, 
-    POST, 
-    PUT, 
+    GET,
+    POST,
+    PUT,
     DELETE
 }
 
+#[derive(PartialEq, Clone, Copy)]
 enum ContentType {
     PLAIN,
     JSON,
     OCTET,
 }
 
+#[derive(PartialEq, Clone, Copy)]
 enum EncodingScheme {
-    GZIP
+    GZIP,
+    BR,
+    DEFLATE,
 }
 
 impl EncodingScheme {
     fn as_str(&self) -> &str {
         match self {
-            EncodingScheme::GZIP => "gzip"
+            EncodingScheme::GZIP => "gzip",
+            EncodingScheme::BR => "br",
+            EncodingScheme::DEFLATE => "deflate",
         }
     }
 
     fn from_str(method: &str) -> Option<Self> {
         match method {
             "gzip" => Some(EncodingScheme::GZIP),
-            _ => None, 
+            "br" => Some(EncodingScheme::BR),
+            "deflate" => Some(EncodingScheme::DEFLATE),
+            _ => None,
         }
     }
+
+    // Fixed server preference order used to break quality-value ties
+    fn preference_order() -> &'static [EncodingScheme] {
+        &[EncodingScheme::BR, EncodingScheme::GZIP, EncodingScheme::DEFLATE]
+    }
 }
 
 impl HTTPMethod {
@@ -162,215 +299,686 @@ impl HTTPRequest {
         }
     }
 
-    fn parse_request(&mut self, stream: &mut TcpStream) -> Result<(), String> {
-        let mut reader = BufReader::new(stream);
-
-        // Read the request line
-        let mut req_line = String::new();
-        if let Err(e) = reader.read_line(&mut req_line) {
-            return Err(format!("Error reading request line: {}", e));
-        }
+    // Parses one request off `reader`. Returns `Ok(true)` when a request was
+    // parsed, `Ok(false)` when the client closed (or idled out) cleanly
+    // before sending another request line, and `Err` on a malformed or
+    // oversized request.
+    fn parse_request(&mut self, reader: &mut BufReader<&mut TcpStream>) -> Result<bool, RequestError> {
+        // Read the request line, skipping any stray blank lines left over
+        // between pipelined requests (RFC 7230 §3.5)
+        let req_line = loop {
+            match read_bounded_line(reader, MAX_REQUEST_LINE_LEN)? {
+                None => return Ok(false),
+                Some(line) if line.is_empty() => continue,
+                Some(line) => break line,
+            }
+        };
 
-        let parts: Vec<&str> = req_line.trim().split_whitespace().collect();
+        let parts: Vec<&str> = req_line.split_whitespace().collect();
         if parts.len() != 3 {
-            return Err("Invalid request line".to_string());
+            return Err(RequestError::bad_request("Invalid request line"));
         }
         if let Some(method) = HTTPMethod::from_str(parts[0]) {
             self.method = method;
         }
         else {
-            return Err(format!("Invalid HTTP method: {}", parts[0]));
+            return Err(RequestError::bad_request(format!("Invalid HTTP method: {}", parts[0])));
+        }
+        if parts[2] != "HTTP/1.0" && parts[2] != "HTTP/1.1" {
+            return Err(RequestError::bad_request(format!("Invalid HTTP version: {}", parts[2])));
         }
-        //These 2 parameters could use some rigorous checking
+        //This parameter could use some rigorous checking
         self.path = parts[1].to_string();
         self.version = parts[2].to_string();
 
-        // Read the headers into a vector
-        let mut headers = Vec::new();
-        for line in reader.by_ref().lines() {
-            let line = match line {
-                Ok(line) => line,
-                Err(e) => return Err(format!("Error reading header line: {}", e)),
+        // Read the headers, enforcing a cap on their count and total size
+        let mut header_count = 0usize;
+        let mut header_bytes = 0usize;
+        loop {
+            let remaining_budget = MAX_HEADER_BYTES.saturating_sub(header_bytes);
+            // Leave room for the line's CRLF, which the byte budget doesn't count
+            let line = match read_bounded_line(reader, (remaining_budget + 2).min(MAX_HEADER_LINE_LEN))? {
+                None => return Err(RequestError::bad_request("Connection closed while reading headers")),
+                Some(line) => line,
             };
 
             if line.is_empty() {
                 break;
             }
-            headers.push(line);
-        }
 
-        // Process headers
-        for line in headers {
+            // Only genuine header lines count against the limits, so a
+            // request with exactly MAX_HEADER_COUNT headers can still reach
+            // the terminating blank line instead of being rejected on it
+            if header_count >= MAX_HEADER_COUNT || header_bytes + line.len() > MAX_HEADER_BYTES {
+                return Err(RequestError::too_large("Too many or too large headers"));
+            }
+
+            if line.starts_with(' ') || line.starts_with('\t') {
+                // Obsolete line folding (RFC 7230 §3.2.4) is not supported
+                return Err(RequestError::bad_request("Obsolete line folding is not supported"));
+            }
+
+            header_bytes += line.len();
+            header_count += 1;
+
             let mut header_parts = line.splitn(2, ':');
-            if let (Some(key), Some(value)) = (header_parts.next(), header_parts.next()) {
-                self.headers.insert(key.trim().to_string(), value.trim().to_string());
+            let (key, value) = match (header_parts.next(), header_parts.next()) {
+                (Some(key), Some(value)) => (key.trim().to_string(), value.trim().to_string()),
+                _ => return Err(RequestError::bad_request(format!("Malformed header line: {}", line))),
+            };
+
+            if key.eq_ignore_ascii_case("Content-Length")
+                && self.headers.keys().any(|k| k.eq_ignore_ascii_case("Content-Length"))
+            {
+                return Err(RequestError::bad_request("Duplicate Content-Length header"));
+            }
+
+            self.headers.insert(key, value);
+        }
+
+        // A client sending `Expect: 100-continue` waits for this interim
+        // status before it streams the body, so send it before reading one
+        if let Some(expect) = self.headers.get("Expect") {
+            if expect.eq_ignore_ascii_case("100-continue") {
+                reader.get_mut().write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+                    .map_err(|e| RequestError::bad_request(format!("Error sending 100 Continue: {}", e)))?;
             }
         }
 
-        // Read the optional body
-        if let Some(length_str) = self.headers.get("Content-Length") {
-            
+        // Read the optional body, honoring Transfer-Encoding: chunked ahead
+        // of Content-Length per RFC 7230 §3.3.3
+        if let Some(transfer_encoding) = self.headers.get("Transfer-Encoding") {
+            if !transfer_encoding.eq_ignore_ascii_case("chunked") {
+                return Err(RequestError::bad_request(format!("Unsupported Transfer-Encoding: {}", transfer_encoding)));
+            }
+
+            let body = read_chunked_body(reader)?;
+            self.body = if body.is_empty() {
+                None
+            } else {
+                match String::from_utf8(body) {
+                    Ok(body_str) => Some(body_str),
+                    Err(e) => return Err(RequestError::bad_request(format!("Error parsing body as UTF-8: {}", e))),
+                }
+            };
+        } else if let Some(length_str) = self.headers.get("Content-Length") {
+
             let length: usize = match length_str.parse() {
                 Ok(len) => len,
-                Err(e) => return Err(format!("Error parsing Content-Length: {}", e)),
+                Err(e) => return Err(RequestError::bad_request(format!("Error parsing Content-Length: {}", e))),
             };
 
-            let mut body = vec![0; length];
-            if let Err(e) = reader.read_exact(&mut body) {
-                return Err(format!("Error reading body: {}", e));
+            if length > MAX_BODY_SIZE {
+                return Err(RequestError::too_large("Content-Length exceeds the configured limit"));
+            }
+
+            // Read incrementally instead of pre-zeroing a `length`-sized
+            // buffer, so a bound-but-still-large Content-Length can't make
+            // the server commit the memory before a single byte has arrived
+            let mut body = Vec::with_capacity(length.min(MAX_HEADER_LINE_LEN));
+            let mut remaining = length;
+            let mut chunk = [0u8; 8192];
+            while remaining > 0 {
+                let to_read = remaining.min(chunk.len());
+                if let Err(e) = reader.read_exact(&mut chunk[..to_read]) {
+                    return Err(RequestError::bad_request(format!("Error reading body: {}", e)));
+                }
+                body.extend_from_slice(&chunk[..to_read]);
+                remaining -= to_read;
             }
 
             self.body = match String::from_utf8(body) {
                 Ok(body_str) => Some(body_str),
-                Err(e) => return Err(format!("Error parsing body as UTF-8: {}", e)),
+                Err(e) => return Err(RequestError::bad_request(format!("Error parsing body as UTF-8: {}", e))),
             };
         } else {
             self.body = None;
         }
 
-        Ok(())
+        Ok(true)
     }
 }
 
 
-fn handle_encoding(request: &HTTPRequest, response: &mut HTTPResponse) {
-    if let Some(encoding_schemes) = request.headers.get("Accept-Encoding") {
-        let encodings: Vec<&str> = encoding_schemes
-        .split(',')
-        .map(|s| s.trim())
-        .collect();
+// Parses an Accept-Encoding header into (token -> quality) pairs, where the
+// token may be a concrete coding (e.g. "gzip") or the "*" wildcard.
+fn parse_accept_encoding(header: &str) -> HashMap<String, f32> {
+    let mut qualities = HashMap::new();
 
-        if encodings.iter().any(|&e| e == EncodingScheme::GZIP.as_str()) {
-            response.headers.insert(
-                "Content-Encoding".to_string(),
-                EncodingScheme::GZIP.as_str().to_string()
-            );
+    for element in header.split(',') {
+        let element = element.trim();
+        if element.is_empty() {
+            continue;
+        }
 
-            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-            encoder.write_all(response.body.as_bytes()).expect("Failed to compress the data!?");
-            let compressed_body = encoder.finish().expect("Failed to finish compression!?");
+        let mut parts = element.splitn(2, ";q=");
+        let token = parts.next().unwrap_or("").trim().to_lowercase();
+        let quality = match parts.next() {
+            Some(q) => q.trim().parse::<f32>().unwrap_or(1.0),
+            None => 1.0,
+        };
 
-            response.set_encoded_body(compressed_body);
+        if token.is_empty() {
+            continue;
         }
+
+        qualities.insert(token, quality.clamp(0.0, 1.0));
     }
+
+    qualities
 }
 
-// This function encapsulates the behaviour of the HTTP server
-fn http_server_response(request: &HTTPRequest) -> HTTPResponse {
-    let path = request.path.as_str();
-    let is_echo_endpoint: bool = path.starts_with("/echo");
-    let is_agent_endpoint: bool = path.starts_with("/user-agent");
-    let is_file_endpoint: bool = path.starts_with("/files");
-
-    //Give default values to endpoints
-    let (status_code, status_msg) = match path { 
-        "/" => (200, "OK"),
-        _ if is_echo_endpoint => (200, "OK"),
-        _ if is_agent_endpoint => (200, "OK"),
-        _ if is_file_endpoint => (200, "OK"),
-        _ => (404, "Not Found"),
+// Resolves the effective quality value for a coding given the parsed
+// Accept-Encoding map, falling back to the "*" wildcard weight when the
+// coding isn't listed explicitly.
+fn effective_quality(qualities: &HashMap<String, f32>, token: &str) -> Option<f32> {
+    qualities.get(token).copied().or_else(|| qualities.get("*").copied())
+}
+
+// Per-scheme compression tuning, plus the minimum body size worth
+// compressing at all
+struct CompressionConfig {
+    gzip_level: u32,
+    deflate_level: u32,
+    brotli_quality: u32,
+    min_body_size: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            gzip_level: 6,
+            deflate_level: 6,
+            brotli_quality: 11,
+            min_body_size: 256,
+        }
+    }
+}
+
+fn handle_encoding(request: &HTTPRequest, response: &mut HTTPResponse, config: &CompressionConfig) {
+    // Already-compressed content and previously-encoded bodies are never
+    // (re-)compressed
+    if response.content_type == ContentType::OCTET || response.is_encoded {
+        return;
+    }
+
+    // Bodies under the threshold still go through full negotiation below so
+    // that a total rejection (406) is honored; the threshold only decides
+    // whether to actually spend time compressing a body that qualifies
+    let below_threshold = response.body.len() < config.min_body_size;
+
+    let encoding_schemes = match request.headers.get("Accept-Encoding") {
+        Some(header) => header,
+        None => return,
     };
 
-    let mut response = HTTPResponse::new(
-        request.version.clone(),
-        status_code,
-        status_msg.to_string(),
+    let qualities = parse_accept_encoding(encoding_schemes);
+
+    let mut best: Option<(EncodingScheme, f32)> = None;
+    for scheme in EncodingScheme::preference_order() {
+        if let Some(q) = effective_quality(&qualities, scheme.as_str()) {
+            if q > 0.0 {
+                let better = match best {
+                    Some((_, best_q)) => q > best_q,
+                    None => true,
+                };
+                if better {
+                    best = Some((*scheme, q));
+                }
+            }
+        }
+    }
+
+    let chosen = match best {
+        Some((scheme, _)) => scheme,
+        None => {
+            let identity_allowed = match effective_quality(&qualities, "identity") {
+                Some(q) => q > 0.0,
+                None => !qualities.contains_key("*") || qualities.get("*").copied().unwrap_or(1.0) > 0.0,
+            };
+
+            if identity_allowed {
+                return;
+            }
+
+            response.status_code = 406;
+            response.status_msg = "Not Acceptable".to_string();
+            response.set_body(String::new());
+            return;
+        }
+    };
+
+    if below_threshold {
+        return;
+    }
+
+    response.headers.insert(
+        "Content-Encoding".to_string(),
+        chosen.as_str().to_string()
     );
 
-    if is_echo_endpoint {
-        response.content_type = ContentType::PLAIN;
-        response.headers.insert(
-            "Content-Type".to_string(),
-             response.content_type.as_str().to_string()
-        );
-        response.set_body(path.trim_start_matches("/echo/").to_string());
-    } 
-    else if is_agent_endpoint {
-        response.content_type = ContentType::PLAIN;
-        response.headers.insert(
-            "Content-Type".to_string(),
-            response.content_type.as_str().to_string()
-        );
+    let compressed_body = match chosen {
+        EncodingScheme::GZIP => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::new(config.gzip_level));
+            encoder.write_all(response.body.as_bytes()).expect("Failed to compress the data!?");
+            encoder.finish().expect("Failed to finish compression!?")
+        }
+        EncodingScheme::DEFLATE => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::new(config.deflate_level));
+            encoder.write_all(response.body.as_bytes()).expect("Failed to compress the data!?");
+            encoder.finish().expect("Failed to finish compression!?")
+        }
+        EncodingScheme::BR => {
+            let mut compressed = Vec::new();
+            {
+                let mut encoder = CompressorWriter::new(&mut compressed, 4096, config.brotli_quality, 22);
+                encoder.write_all(response.body.as_bytes()).expect("Failed to compress the data!?");
+            }
+            compressed
+        }
+    };
+
+    response.set_encoded_body(compressed_body);
+}
+
+// Builds a strong ETag from a file's size and modification time, good enough
+// to detect changes without hashing the whole file
+fn file_etag(size: u64, modified: SystemTime) -> String {
+    let mtime_secs = modified.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    format!("\"{:x}-{:x}\"", size, mtime_secs)
+}
+
+#[derive(Clone, Copy)]
+enum RangeKind {
+    Full,
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+// Parses a single-range `Range: bytes=...` header against the resource's
+// total size, supporting the `start-end`, `start-` and `-suffixLength`
+// forms. A missing or unparseable header falls back to `Full`.
+fn resolve_range(header: Option<&String>, total: u64) -> RangeKind {
+    let header = match header {
+        Some(h) => h,
+        None => return RangeKind::Full,
+    };
+
+    let spec = match header.strip_prefix("bytes=") {
+        Some(s) => s,
+        None => return RangeKind::Full,
+    };
+
+    // Only the first range of a (possibly multi-range) request is honored
+    let spec = spec.split(',').next().unwrap_or("").trim();
+    let (start_str, end_str) = match spec.split_once('-') {
+        Some(parts) => parts,
+        None => return RangeKind::Full,
+    };
+
+    if start_str.is_empty() {
+        let suffix_len: u64 = match end_str.parse() {
+            Ok(v) => v,
+            Err(_) => return RangeKind::Full,
+        };
+
+        if suffix_len == 0 || total == 0 {
+            return RangeKind::Unsatisfiable;
+        }
+
+        return RangeKind::Partial(total.saturating_sub(suffix_len), total - 1);
+    }
 
-        if let Some(user_agent) = request.headers.get("User-Agent") {
-            response.set_body(user_agent.to_string());
+    let start: u64 = match start_str.parse() {
+        Ok(v) => v,
+        Err(_) => return RangeKind::Full,
+    };
+
+    if start >= total {
+        return RangeKind::Unsatisfiable;
+    }
+
+    let end: u64 = if end_str.is_empty() {
+        total - 1
+    } else {
+        match end_str.parse::<u64>() {
+            Ok(v) => v.min(total - 1),
+            Err(_) => return RangeKind::Full,
+        }
+    };
+
+    if end < start {
+        return RangeKind::Unsatisfiable;
+    }
+
+    RangeKind::Partial(start, end)
+}
+
+// A path-pattern segment as registered on a route
+enum PatternSegment {
+    Literal(String),
+    // A single named segment, e.g. `{id}`
+    Param(String),
+    // Matches any single segment without capturing it
+    Wildcard,
+}
+
+// A registered route: the method/pattern pair it matches, and the handler
+// invoked on a match
+struct Route {
+    method: HTTPMethod,
+    pattern: Vec<PatternSegment>,
+    handler: fn(&HTTPRequest, &HashMap<String, String>) -> HTTPResponse,
+}
+
+enum RouteOutcome {
+    Matched(fn(&HTTPRequest, &HashMap<String, String>) -> HTTPResponse, HashMap<String, String>),
+    // The path matched at least one route, just not for this method
+    MethodNotAllowed(Vec<String>),
+    NotFound,
+}
+
+// Dispatch core: routes are registered as (method, pattern) pairs against
+// handler functions, where a pattern supports named segments (`{id}`) and
+// `*` wildcards. A trailing named segment greedily captures the rest of
+// the path, which lets `/files/{name}` and `/echo/{text}` keep matching
+// sub-paths the way the old `trim_start_matches` branching did.
+struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    fn new() -> Self {
+        Router { routes: Vec::new() }
+    }
+
+    fn register(&mut self, method: HTTPMethod, pattern: &str, handler: fn(&HTTPRequest, &HashMap<String, String>) -> HTTPResponse) {
+        self.routes.push(Route {
+            method,
+            pattern: parse_pattern(pattern),
+            handler,
+        });
+    }
+
+    fn dispatch(&self, request: &HTTPRequest) -> RouteOutcome {
+        let path_segments = split_path(&request.path);
+        let mut allowed_methods = Vec::new();
+        let mut path_matched = false;
+
+        for route in &self.routes {
+            let params = match match_pattern(&route.pattern, &path_segments) {
+                Some(params) => params,
+                None => continue,
+            };
+
+            path_matched = true;
+            if route.method == request.method {
+                return RouteOutcome::Matched(route.handler, params);
+            }
+            allowed_methods.push(route.method.as_str().to_string());
+        }
+
+        if path_matched {
+            RouteOutcome::MethodNotAllowed(allowed_methods)
         } else {
-            response.status_code = 404;
-            response.status_msg = "User-Agent header not found".to_string();
-        }
-    }
-    else if is_file_endpoint {
-        // Extract the file name from the path
-        let file_name = path.trim_start_matches("/files/");
-        // Collect command line arguments and build the absolute path to the file
-        let env_args: Vec<String> = env::args().collect();
-        let directory = env_args.get(2).unwrap_or(&String::from(".")).clone();
-        let file_path = format!("{}/{}", directory, file_name);
-
-        match request.method {
-            //Overwrite by default
-            HTTPMethod::POST => {
-                match fs::File::create(&file_path) {
-                    Ok(mut file) => {
-                        let file_content = match &request.body {
-                            Some(body) => body.to_string(),
-                            None => "".to_string(),
-                        };
-                        
-                        //Write the file and check for any errors
-                        if let Err(e) = file.write_all(file_content.as_bytes()) {
-                            response.status_code = 500;
-                            response.status_msg = "Internal Server Error".to_string();
-                            response.set_body(format!("Failed to write to file: {}", e));
-                        }
+            RouteOutcome::NotFound
+        }
+    }
+}
 
-                        response.status_code = 201;
-                        response.status_msg = "Created".to_string();
-                    }
-                    Err(e) => {
-                        response.status_code = 500;
-                        response.status_msg = "Internal Server Error".to_string();
-                        response.set_body(format!("Error creating file: {}", e));
-                    }
+fn split_path(path: &str) -> Vec<&str> {
+    path.trim_matches('/').split('/').filter(|s| !s.is_empty()).collect()
+}
+
+fn parse_pattern(pattern: &str) -> Vec<PatternSegment> {
+    split_path(pattern)
+        .iter()
+        .map(|segment| {
+            if segment.starts_with('{') && segment.ends_with('}') {
+                PatternSegment::Param(segment[1..segment.len() - 1].to_string())
+            } else if *segment == "*" {
+                PatternSegment::Wildcard
+            } else {
+                PatternSegment::Literal(segment.to_string())
+            }
+        })
+        .collect()
+}
+
+fn match_pattern(pattern: &[PatternSegment], path_segments: &[&str]) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+
+    for (i, segment) in pattern.iter().enumerate() {
+        let is_last = i == pattern.len() - 1;
+
+        match segment {
+            PatternSegment::Param(name) if is_last => {
+                if i >= path_segments.len() {
+                    return None;
                 }
+                params.insert(name.clone(), path_segments[i..].join("/"));
+                return Some(params);
+            }
+            PatternSegment::Param(name) => match path_segments.get(i) {
+                Some(value) => {
+                    params.insert(name.clone(), value.to_string());
+                }
+                None => return None,
+            },
+            PatternSegment::Wildcard => {
+                path_segments.get(i)?;
+            }
+            PatternSegment::Literal(literal) => {
+                if path_segments.get(i) != Some(&literal.as_str()) {
+                    return None;
+                }
+            }
+        }
+    }
+
+    if path_segments.len() != pattern.len() {
+        return None;
+    }
+
+    Some(params)
+}
+
+fn build_router() -> Router {
+    let mut router = Router::new();
+    router.register(HTTPMethod::GET, "/", handle_root);
+    router.register(HTTPMethod::GET, "/echo/{text}", handle_echo);
+    router.register(HTTPMethod::GET, "/user-agent", handle_user_agent);
+    router.register(HTTPMethod::GET, "/files/{name}", handle_files_get);
+    router.register(HTTPMethod::POST, "/files/{name}", handle_files_post);
+    router
+}
+
+fn handle_root(request: &HTTPRequest, _params: &HashMap<String, String>) -> HTTPResponse {
+    HTTPResponse::new(request.version.clone(), 200, "OK".to_string())
+}
+
+fn handle_echo(request: &HTTPRequest, params: &HashMap<String, String>) -> HTTPResponse {
+    let mut response = HTTPResponse::new(request.version.clone(), 200, "OK".to_string());
+    response.content_type = ContentType::PLAIN;
+    response.headers.insert(
+        "Content-Type".to_string(),
+        response.content_type.as_str().to_string()
+    );
+    response.set_body(params.get("text").cloned().unwrap_or_default());
+    response
+}
+
+fn handle_user_agent(request: &HTTPRequest, _params: &HashMap<String, String>) -> HTTPResponse {
+    let mut response = HTTPResponse::new(request.version.clone(), 200, "OK".to_string());
+    response.content_type = ContentType::PLAIN;
+    response.headers.insert(
+        "Content-Type".to_string(),
+        response.content_type.as_str().to_string()
+    );
+
+    if let Some(user_agent) = request.headers.get("User-Agent") {
+        response.set_body(user_agent.to_string());
+    } else {
+        response.status_code = 404;
+        response.status_msg = "User-Agent header not found".to_string();
+    }
+
+    response
+}
+
+// Looks up the value following a `--flag` in the process' command-line
+// arguments, e.g. `arg_value("--directory")` for `--directory /tmp`
+fn arg_value(flag: &str) -> Option<String> {
+    let args: Vec<String> = env::args().collect();
+    args.iter().position(|a| a == flag).and_then(|i| args.get(i + 1)).cloned()
+}
+
+fn files_directory() -> String {
+    arg_value("--directory").unwrap_or_else(|| ".".to_string())
+}
+
+fn handle_files_post(request: &HTTPRequest, params: &HashMap<String, String>) -> HTTPResponse {
+    let mut response = HTTPResponse::new(request.version.clone(), 201, "Created".to_string());
+    let file_path = format!("{}/{}", files_directory(), params.get("name").cloned().unwrap_or_default());
+
+    match fs::File::create(&file_path) {
+        Ok(mut file) => {
+            let file_content = match &request.body {
+                Some(body) => body.to_string(),
+                None => "".to_string(),
+            };
+
+            //Write the file and check for any errors
+            if let Err(e) = file.write_all(file_content.as_bytes()) {
+                response.status_code = 500;
+                response.status_msg = "Internal Server Error".to_string();
+                response.set_body(format!("Failed to write to file: {}", e));
             }
-            HTTPMethod::GET => {
-                let response_body = match fs::read(&file_path) {
-                    Ok(file_content) => match String::from_utf8(file_content) {
-                        Ok(content) => {
+        }
+        Err(e) => {
+            response.status_code = 500;
+            response.status_msg = "Internal Server Error".to_string();
+            response.set_body(format!("Error creating file: {}", e));
+        }
+    }
+
+    response
+}
+
+fn handle_files_get(request: &HTTPRequest, params: &HashMap<String, String>) -> HTTPResponse {
+    let mut response = HTTPResponse::new(request.version.clone(), 200, "OK".to_string());
+    let file_path = format!("{}/{}", files_directory(), params.get("name").cloned().unwrap_or_default());
+
+    match fs::metadata(&file_path) {
+        Ok(metadata) => {
+            let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+            let etag = file_etag(metadata.len(), modified);
+            let last_modified = fmt_http_date(modified);
+
+            // Per RFC 7232 §6, If-None-Match takes priority and
+            // If-Modified-Since is ignored when both are sent
+            let not_modified = if let Some(if_none_match) = request.headers.get("If-None-Match") {
+                if_none_match.split(',').map(|t| t.trim()).any(|t| t == etag || t == "*")
+            } else if let Some(if_modified_since) = request.headers.get("If-Modified-Since") {
+                match parse_http_date(if_modified_since) {
+                    // Compare at whole-second precision: the advertised
+                    // Last-Modified (and any If-Modified-Since echoing it
+                    // back) is HTTP-date formatted and loses sub-second
+                    // precision, so comparing the raw SystemTimes would
+                    // never consider the file unchanged
+                    Ok(since) => {
+                        let modified_secs = modified.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                        let since_secs = since.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                        modified_secs <= since_secs
+                    }
+                    Err(_) => false,
+                }
+            } else {
+                false
+            };
+
+            response.headers.insert("ETag".to_string(), etag);
+            response.headers.insert("Last-Modified".to_string(), last_modified);
+
+            if not_modified {
+                response.status_code = 304;
+                response.status_msg = "Not Modified".to_string();
+                response.set_body(String::new());
+            } else {
+                let range = resolve_range(request.headers.get("Range"), metadata.len());
+
+                if let RangeKind::Unsatisfiable = range {
+                    response.status_code = 416;
+                    response.status_msg = "Range Not Satisfiable".to_string();
+                    response.headers.insert(
+                        "Content-Range".to_string(),
+                        format!("bytes */{}", metadata.len())
+                    );
+                    response.set_body(String::new());
+                } else {
+                    match fs::read(&file_path) {
+                        Ok(file_content) => {
+                            let total = file_content.len() as u64;
+                            // Carry the file through as raw bytes rather than
+                            // forcing it through String, so binary/media
+                            // files (which aren't valid UTF-8) can be served
+                            let slice = match range {
+                                RangeKind::Partial(start, end) => &file_content[start as usize..=end as usize],
+                                _ => &file_content[..],
+                            };
+
                             response.content_type = ContentType::OCTET;
                             response.headers.insert(
                                 "Content-Type".to_string(),
                                 response.content_type.as_str().to_string(),
                             );
-                            content
+
+                            if let RangeKind::Partial(start, end) = range {
+                                response.status_code = 206;
+                                response.status_msg = "Partial Content".to_string();
+                                response.headers.insert(
+                                    "Content-Range".to_string(),
+                                    format!("bytes {}-{}/{}", start, end, total)
+                                );
+                            } else {
+                                response.headers.insert("Accept-Ranges".to_string(), "bytes".to_string());
+                            }
+
+                            response.set_encoded_body(slice.to_vec());
                         }
                         Err(e) => {
-                            eprintln!("Error converting file content to String: {}", e);
-                            response.status_code = 500;
-                            response.status_msg = "Internal Server Error".to_string();
-                            //Graceful error message
-                            "".to_string()
+                            eprintln!("Error reading file: {}", e);
+                            response.status_code = 404;
+                            response.status_msg = "Not Found".to_string();
+                            response.set_body(String::new());
                         }
-                    },
-                    Err(e) => {
-                        eprintln!("Error reading file: {}", e);
-                        response.status_code = 404;
-                        response.status_msg = "Not Found".to_string();
-                        "".to_string()
-                    }
-                };
-            
-                response.set_body(response_body);
-            }
-            _ => {
-                response.status_code = 405;
-                response.status_msg = "Method Not Allowed".to_string();
+                    };
+                }
             }
-        }    
+        }
+        Err(e) => {
+            eprintln!("Error reading file: {}", e);
+            response.status_code = 404;
+            response.status_msg = "Not Found".to_string();
+        }
     }
 
-    handle_encoding(&request, &mut response);
+    response
+}
+
+// This function encapsulates the behaviour of the HTTP server
+fn http_server_response(router: &Router, request: &HTTPRequest, compression_config: &CompressionConfig) -> HTTPResponse {
+    let mut response = match router.dispatch(request) {
+        RouteOutcome::Matched(handler, params) => handler(request, &params),
+        RouteOutcome::MethodNotAllowed(allowed_methods) => {
+            let mut response = HTTPResponse::new(request.version.clone(), 405, "Method Not Allowed".to_string());
+            response.headers.insert("Allow".to_string(), allowed_methods.join(", "));
+            response
+        }
+        RouteOutcome::NotFound => HTTPResponse::new(request.version.clone(), 404, "Not Found".to_string()),
+    };
+
+    handle_encoding(request, &mut response, compression_config);
 
     // Set Content-Length header
     let content_length = if response.is_encoded {
@@ -388,45 +996,194 @@ fn http_server_response(request: &HTTPRequest) -> HTTPResponse {
 }
 
 
-fn handle_tcp_stream_connect(tcp_stream: &mut TcpStream) -> Result<(), std::io::Error>{
+// Decides whether the connection should stay open after this exchange,
+// honoring an explicit `Connection` header and falling back to the
+// protocol-version default (keep-alive for HTTP/1.1, close for HTTP/1.0)
+fn should_keep_alive(request: &HTTPRequest) -> bool {
+    match request.headers.get("Connection").map(|v| v.to_lowercase()) {
+        Some(v) if v == "close" => false,
+        Some(v) if v == "keep-alive" => true,
+        _ => request.version == "HTTP/1.1",
+    }
+}
+
+fn handle_tcp_stream_connect(tcp_stream: &mut TcpStream, router: &Router, compression_config: &CompressionConfig) -> Result<(), std::io::Error>{
     println!(
         "Accepted new connection from TCP connection with socket address {}",
          tcp_stream.peer_addr()?
     );
 
-    let mut request: HTTPRequest = HTTPRequest::new();
+    tcp_stream.set_read_timeout(Some(CONNECTION_IDLE_TIMEOUT))?;
+    let mut reader = BufReader::new(&mut *tcp_stream);
 
-    // Parse the incoming HTTP request
-    if let Err(e) = request.parse_request(tcp_stream) {
-        eprintln!("Error parsing request: {}", e);
-        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e));
-    }
+    loop {
+        let mut request: HTTPRequest = HTTPRequest::new();
+
+        // Parse the next request off the (possibly reused) connection
+        match request.parse_request(&mut reader) {
+            Ok(true) => {}
+            Ok(false) => break, // client closed, or idled past the timeout
+            Err(e) => {
+                eprintln!("Error parsing request: {}", e);
+
+                let mut response = HTTPResponse::new(
+                    "HTTP/1.1".to_string(),
+                    e.status_code,
+                    e.status_msg.to_string(),
+                );
+                response.headers.insert("Connection".to_string(), "close".to_string());
+                response.headers.insert("Content-Length".to_string(), "0".to_string());
+                let _ = reader.get_mut().write_all(&response.to_vec());
+                break;
+            }
+        }
 
-    // Let the server elaborate a response for the request
-    let response = http_server_response(&request);
-    let response_vec = response.to_vec();
+        // Let the server elaborate a response for the request
+        let mut response = http_server_response(router, &request, compression_config);
+        let keep_alive = should_keep_alive(&request);
+        response.headers.insert(
+            "Connection".to_string(),
+            (if keep_alive { "keep-alive" } else { "close" }).to_string()
+        );
+        let response_vec = response.to_vec();
+
+        // Write the response to the TCP connection (Stream)
+        if let Err(e) = reader.get_mut().write_all(&response_vec) {
+            eprintln!("Error sending the response: {}", e);
+            return Err(std::io::Error::new(std::io::ErrorKind::ConnectionAborted, e));
+        }
 
-    // Write the response to the TCP connection (Stream)
-    if let Err(e) = tcp_stream.write_all(&response_vec) {
-        eprintln!("Error sending the response: {}", e);
-        return Err(std::io::Error::new(std::io::ErrorKind::ConnectionAborted, e));
+        if !keep_alive {
+            break;
+        }
     }
 
     Ok(())
 }
 
+const DEFAULT_POOL_SIZE: usize = 8;
+const CONNECTION_QUEUE_CAPACITY: usize = 128;
+
+fn pool_size() -> usize {
+    arg_value("--pool-size")
+        .and_then(|v| v.parse().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(DEFAULT_POOL_SIZE)
+}
+
+// A long-lived worker, each running `handle_tcp_stream_connect` for every
+// connection it pulls off the shared job queue
+struct Worker {
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Worker {
+    fn new(
+        jobs: Arc<Mutex<mpsc::Receiver<TcpStream>>>,
+        router: Arc<Router>,
+        compression_config: Arc<CompressionConfig>,
+    ) -> Worker {
+        let thread = thread::spawn(move || loop {
+            // Recover from a poisoned lock instead of panicking, so a panic
+            // while handling one connection can't take down every worker.
+            // The guard is scoped to just the recv() so it's dropped before
+            // handling the connection, letting other workers pull jobs
+            // while this one is busy.
+            let stream = match jobs.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).recv() {
+                Ok(stream) => stream,
+                Err(_) => break, // the queue was closed down, shut down too
+            };
+
+            // Catch a panic from handling one connection instead of letting
+            // it unwind out of the thread: an unhandled panic here would
+            // permanently shrink the fixed-size pool by one, and a handful
+            // of malformed requests could drain it to zero
+            let mut stream = stream;
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                handle_tcp_stream_connect(&mut stream, &router, &compression_config)
+            }));
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => eprintln!("Error handling connection: {}", e),
+                Err(_) => eprintln!("Worker panicked while handling a connection"),
+            }
+        });
+
+        Worker { thread: Some(thread) }
+    }
+}
+
+// A fixed-size pool of workers fed by a bounded job queue, so a burst of
+// connections can't spawn unbounded OS threads
+struct ThreadPool {
+    workers: Vec<Worker>,
+    sender: Option<mpsc::SyncSender<TcpStream>>,
+}
+
+impl ThreadPool {
+    fn new(size: usize, queue_capacity: usize, router: Arc<Router>, compression_config: Arc<CompressionConfig>) -> ThreadPool {
+        let (sender, receiver) = mpsc::sync_channel(queue_capacity);
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..size)
+            .map(|_| Worker::new(Arc::clone(&receiver), Arc::clone(&router), Arc::clone(&compression_config)))
+            .collect();
+
+        ThreadPool { workers, sender: Some(sender) }
+    }
+
+    // Enqueues a connection, handing it back if the queue is already full
+    // so the caller can apply backpressure instead of blocking forever
+    fn try_dispatch(&self, stream: TcpStream) -> Result<(), TcpStream> {
+        match &self.sender {
+            Some(sender) => sender.try_send(stream).map_err(|e| match e {
+                mpsc::TrySendError::Full(stream) => stream,
+                mpsc::TrySendError::Disconnected(stream) => stream,
+            }),
+            None => Err(stream),
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // Closing the channel lets idle workers fall out of their recv loop
+        drop(self.sender.take());
+
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+// Replies with a final status without handing the connection to a worker,
+// used when the job queue is saturated
+fn respond_with_503(mut stream: TcpStream) {
+    // Bound the write so a client that never reads can't block the lone
+    // accept loop indefinitely
+    let _ = stream.set_write_timeout(Some(Duration::from_secs(2)));
+
+    let mut response = HTTPResponse::new("HTTP/1.1".to_string(), 503, "Service Unavailable".to_string());
+    response.headers.insert("Connection".to_string(), "close".to_string());
+    response.headers.insert("Content-Length".to_string(), "0".to_string());
+    let _ = stream.write_all(&response.to_vec());
+}
+
 fn main() {
+    let router = Arc::new(build_router());
+    let compression_config = Arc::new(CompressionConfig::default());
+    let pool = ThreadPool::new(pool_size(), CONNECTION_QUEUE_CAPACITY, Arc::clone(&router), Arc::clone(&compression_config));
+
     let listener = TcpListener::bind("127.0.0.1:4221").unwrap();
     for stream in listener.incoming() {
         match stream {
-            Ok(mut stream) => {
-                // Multithreaded solution 
-                thread::spawn(move || {
-                    if let Err(e) = handle_tcp_stream_connect(&mut stream) {
-                        eprintln!("Error handling connection: {}", e);
-                    }
-                });
-
+            Ok(stream) => {
+                if let Err(rejected) = pool.try_dispatch(stream) {
+                    eprintln!("Worker queue is full, rejecting connection");
+                    respond_with_503(rejected);
+                }
             }
             Err(e) => {
                 eprintln!("Error accepting connection: {}", e);